@@ -2,10 +2,14 @@ extern crate autopilot;
 extern crate pyo3;
 use autopilot::geometry::{Point, Rect, Size};
 use image;
-use image::{ImageFormat, ImageResult, Pixel, Rgba};
+use image::{GenericImage, ImageFormat, ImageResult, Pixel, Rgba};
 use internal::FromImageError;
+use pyo3::class::basic::{CompareOp, PyObjectProtocol};
 use pyo3::prelude::*;
+use pyo3::PyBytes;
+use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::path::Path;
 
 #[py::class]
@@ -72,6 +76,118 @@ impl<'a> Bitmap {
         Ok(result)
     }
 
+    /// Decodes the image contained in `data`, an in-memory buffer of
+    /// encoded image bytes. The image's format is inferred from the
+    /// buffer's contents unless `format` is given, in which case it
+    /// is interpreted the same way as in `save`.
+    ///
+    /// Exceptions:
+    ///     - `ValueError` is thrown if `format` is unknown or the
+    ///       image couldn't be parsed.
+    #[classmethod]
+    fn from_bytes(cls: &PyType, data: Vec<u8>, format: Option<&str>) -> PyResult<&Bitmap> {
+        let image = if let Some(format) = format {
+            let fmt = try!(image_format_from_extension(format).ok_or_else(|| {
+                exc::ValueError::new(format!("Unknown format {}", format))
+            }));
+            try!(image::load_from_memory_with_format(&data, fmt).map_err(FromImageError::from))
+        } else {
+            try!(image::load_from_memory(&data).map_err(FromImageError::from))
+        };
+        let bmp = autopilot::bitmap::Bitmap::new(image, None);
+        let result = try!(cls.py().init_ref(|t| {
+            Bitmap {
+                bitmap: bmp,
+                token: t,
+            }
+        }));
+        Ok(result)
+    }
+
+    /// Constructs a `Bitmap` from a raw RGBA8 pixel buffer, useful
+    /// for feeding frames captured outside of autopy (a camera, a
+    /// video decoder, numpy/ctypes) into `find_color`/`find_bitmap`.
+    /// `row_bytes` is the stride between the start of consecutive
+    /// rows; it defaults to `width * 4` (a tightly packed buffer) and
+    /// must be at least `width * 4`. `data` must be at least `height
+    /// * row_bytes` bytes.
+    ///
+    /// Exceptions:
+    ///     - `ValueError` is thrown if `row_bytes` is smaller than
+    ///       `width * 4`, or `data` is too small for the given
+    ///       `width`, `height`, and `row_bytes`.
+    #[classmethod]
+    fn from_rgba(
+        cls: &PyType,
+        width: u32,
+        height: u32,
+        data: Vec<u8>,
+        row_bytes: Option<u32>,
+        scale: Option<f64>,
+    ) -> PyResult<&Bitmap> {
+        let min_row_bytes = (width as usize) * 4;
+        let row_bytes = row_bytes.map(|r| r as usize).unwrap_or(min_row_bytes);
+        if row_bytes < min_row_bytes {
+            return Err(exc::ValueError::new(format!(
+                "row_bytes {} too small for width {} (need >= {})",
+                row_bytes, width, min_row_bytes
+            )));
+        }
+        if data.len() < (height as usize) * row_bytes {
+            return Err(exc::ValueError::new(format!(
+                "Buffer of length {} too small for {}x{} image with row_bytes {}",
+                data.len(),
+                width,
+                height,
+                row_bytes
+            )));
+        }
+        let packed = if row_bytes == min_row_bytes {
+            data
+        } else {
+            let mut packed = Vec::with_capacity(min_row_bytes * height as usize);
+            for row in 0..height as usize {
+                let start = row * row_bytes;
+                packed.extend_from_slice(&data[start..start + min_row_bytes]);
+            }
+            packed
+        };
+        let rgba = try!(
+            image::RgbaImage::from_raw(width, height, packed)
+                .ok_or_else(|| exc::ValueError::new("Buffer too small for image dimensions"))
+        );
+        let image = image::DynamicImage::ImageRgba8(rgba);
+        let bmp = autopilot::bitmap::Bitmap::new(image, scale);
+        let result = try!(cls.py().init_ref(|t| {
+            Bitmap {
+                bitmap: bmp,
+                token: t,
+            }
+        }));
+        Ok(result)
+    }
+
+    /// Encodes the bitmap into an in-memory buffer in the given
+    /// format and returns the result as `bytes`. Supported formats
+    /// are png, gif, and bmp.
+    ///
+    /// Exceptions:
+    ///     - `ValueError` is thrown if `format` is unknown or the
+    ///       image couldn't be encoded.
+    fn to_bytes(&self, format: &str) -> PyResult<&PyBytes> {
+        if let Some(fmt) = image_format_from_extension(format) {
+            let mut buffer: Vec<u8> = Vec::new();
+            try!(
+                self.bitmap
+                    .image
+                    .write_to(&mut buffer, fmt)
+                    .map_err(FromImageError::from)
+            );
+            Ok(PyBytes::new(self.py(), &buffer))
+        } else {
+            Err(exc::ValueError::new(format!("Unknown format {}", format)))
+        }
+    }
 
     /// Returns `(r, g, b)` tuple describing the color at a given
     /// point.
@@ -231,6 +347,85 @@ impl<'a> Bitmap {
         Ok(count)
     }
 
+    /// Attempts to find `needle` inside `rect` in `bmp` from the
+    /// given `start_point` using normalized cross-correlation (NCC)
+    /// on grayscale, rather than exact/tolerance matching. Returns
+    /// the first top-left position whose correlation coefficient is
+    /// `>= threshold`, or `None` if none match. `threshold` ranges
+    /// from -1 to 1, where 1 is a perfect match; unlike
+    /// `find_bitmap`, NCC is invariant to uniform brightness
+    /// shifts/offsets, so it tolerates antialiasing and subpixel
+    /// rendering differences that would defeat tolerance matching.
+    ///
+    /// `rect`, `start_point`, and the returned coordinates are all in
+    /// the same point space as `find_bitmap` (i.e. scaled down by
+    /// `bmp.scale` from the underlying pixel buffer that the
+    /// correlation is actually computed on), so the two methods
+    /// compose with the same rects/points regardless of display
+    /// scale.
+    fn find_bitmap_ncc(
+        &self,
+        needle: &Bitmap,
+        threshold: f64,
+        rect: Option<((f64, f64), (f64, f64))>,
+        start_point: Option<(f64, f64)>,
+    ) -> PyResult<Option<(f64, f64)>> {
+        let rect: Option<Rect> = rect.map(|r| {
+            Rect::new(Point::new((r.0).0, (r.0).1), Size::new((r.1).0, (r.1).1))
+        });
+        let bounds = rect.unwrap_or_else(|| self.bitmap.bounds());
+        let start = start_point
+            .map(|p| Point::new(p.0, p.1))
+            .unwrap_or(bounds.origin);
+        let scale = self.bitmap.scale;
+        let positions = find_bitmap_ncc_positions(
+            &self.bitmap.image,
+            &needle.bitmap.image,
+            threshold,
+            scale_rect(bounds, scale),
+            scale_point(start, scale),
+            false,
+        );
+        Ok(positions
+            .into_iter()
+            .next()
+            .map(|p| (p.x / scale, p.y / scale)))
+    }
+
+    /// Returns list of all `(x, y)` coordinates inside `rect` in
+    /// `bmp` whose normalized cross-correlation with `needle` is `>=
+    /// threshold`, starting from `start_point`. See `find_bitmap_ncc`
+    /// for details on the matching behavior and point-space
+    /// conventions.
+    fn find_every_bitmap_ncc(
+        &self,
+        needle: &Bitmap,
+        threshold: f64,
+        rect: Option<((f64, f64), (f64, f64))>,
+        start_point: Option<(f64, f64)>,
+    ) -> PyResult<Vec<(f64, f64)>> {
+        let rect: Option<Rect> = rect.map(|r| {
+            Rect::new(Point::new((r.0).0, (r.0).1), Size::new((r.1).0, (r.1).1))
+        });
+        let bounds = rect.unwrap_or_else(|| self.bitmap.bounds());
+        let start = start_point
+            .map(|p| Point::new(p.0, p.1))
+            .unwrap_or(bounds.origin);
+        let scale = self.bitmap.scale;
+        let positions = find_bitmap_ncc_positions(
+            &self.bitmap.image,
+            &needle.bitmap.image,
+            threshold,
+            scale_rect(bounds, scale),
+            scale_point(start, scale),
+            true,
+        );
+        Ok(positions
+            .into_iter()
+            .map(|p| (p.x / scale, p.y / scale))
+            .collect())
+    }
+
     /// Returns new bitmap object created from a portion of another.
     ///
     /// Exceptions:
@@ -250,6 +445,118 @@ impl<'a> Bitmap {
         Ok(result)
     }
 
+    /// Returns `(data, row_bytes)`, where `data` is the packed RGBA8
+    /// pixel buffer backing the bitmap and `row_bytes` is the number
+    /// of bytes per row (the stride). Useful for bridging a `Bitmap`
+    /// into numpy or other zero-copy-friendly buffers.
+    fn get_bytes(&self) -> PyResult<(&PyBytes, u64)> {
+        let rgba = self.bitmap.image.to_rgba();
+        let row_bytes = (rgba.width() as u64) * 4;
+        Ok((PyBytes::new(self.py(), &rgba.into_raw()), row_bytes))
+    }
+
+    /// Replaces every pixel inside `rect` (or the whole bitmap, if
+    /// `rect` is `None`) whose `(pixel & mask) operation
+    /// (threshold_color & mask)` holds, packing pixels and
+    /// `threshold_color` into ARGB `u32`s for the comparison. `mask`
+    /// defaults to `0xFFFFFFFF`. Returns the number of pixels
+    /// replaced. Useful for cheaply binarizing or color-keying a
+    /// screengrab before `find_color`.
+    ///
+    /// `operation` must be one of `"<"`, `"<="`, `">"`, `">="`,
+    /// `"=="`, or `"!="`.
+    ///
+    /// Exceptions:
+    ///     - `ValueError` is thrown if `operation` is unrecognized.
+    fn threshold(
+        &mut self,
+        operation: &str,
+        threshold_color: (u8, u8, u8, u8),
+        replace_color: (u8, u8, u8, u8),
+        mask: Option<u32>,
+        rect: Option<((f64, f64), (f64, f64))>,
+    ) -> PyResult<u64> {
+        let compare: fn(u32, u32) -> bool = match operation {
+            "<" => |a, b| a < b,
+            "<=" => |a, b| a <= b,
+            ">" => |a, b| a > b,
+            ">=" => |a, b| a >= b,
+            "==" => |a, b| a == b,
+            "!=" => |a, b| a != b,
+            _ => {
+                return Err(exc::ValueError::new(
+                    format!("Unknown operation {}", operation),
+                ))
+            }
+        };
+        let mask = mask.unwrap_or(0xFFFFFFFF);
+        let (img_width, img_height) = (self.bitmap.image.width(), self.bitmap.image.height());
+        let rect: Option<Rect> = rect.map(|r| {
+            Rect::new(Point::new((r.0).0, (r.0).1), Size::new((r.1).0, (r.1).1))
+        });
+        let (rx, ry, rw, rh) = rect
+            .map(|r| (r.origin.x, r.origin.y, r.size.width, r.size.height))
+            .unwrap_or((0.0, 0.0, img_width as f64, img_height as f64));
+        let x0 = rx.max(0.0) as u32;
+        let y0 = ry.max(0.0) as u32;
+        let x1 = (rx + rw).max(0.0).min(img_width as f64) as u32;
+        let y1 = (ry + rh).max(0.0).min(img_height as f64) as u32;
+
+        let threshold_packed = pack_argb(threshold_color) & mask;
+        let replace_pixel = Rgba([replace_color.0, replace_color.1, replace_color.2, replace_color.3]);
+        let mut count = 0u64;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel = self.bitmap.image.get_pixel(x, y);
+                let (r, g, b, a) = pixel.channels4();
+                let packed = pack_argb((r, g, b, a)) & mask;
+                if compare(packed, threshold_packed) {
+                    self.bitmap.image.put_pixel(x, y, replace_pixel);
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns `True` if `other` is equal to this bitmap, within
+    /// `tolerance` (see `find_color`/`find_bitmap` for how tolerance
+    /// is defined). Unlike exact equality, this tolerates the minor
+    /// compression/antialiasing differences that make two
+    /// screenshots of "the same" screen differ byte-for-byte.
+    fn is_equal(&self, other: &Bitmap, tolerance: Option<f64>) -> PyResult<bool> {
+        Ok(self.bitmap.bitmap_eq(&other.bitmap, tolerance))
+    }
+
+    /// Returns a 64-bit average-hash of the bitmap: the image is
+    /// downscaled to 8x8, converted to grayscale, and bit *i* of the
+    /// result is set when pixel *i* (in row-major order) is brighter
+    /// than the mean luminance. Use `hamming_distance` to compare two
+    /// hashes for near-duplicate detection, e.g. polling for "has the
+    /// screen changed", which exact equality can't do reliably.
+    fn perceptual_hash(&self) -> PyResult<u64> {
+        let small = self.bitmap
+            .image
+            .resize_exact(8, 8, image::FilterType::Triangle)
+            .to_luma();
+        let pixels = small.into_raw();
+        let mean = pixels.iter().map(|&p| p as u64).sum::<u64>() as f64 / pixels.len() as f64;
+        let mut hash: u64 = 0;
+        for (i, &p) in pixels.iter().enumerate() {
+            if (p as f64) > mean {
+                hash |= 1 << i;
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Returns the Hamming distance (popcount of the XOR) between two
+    /// `perceptual_hash` values.
+    #[staticmethod]
+    fn hamming_distance(a: u64, b: u64) -> PyResult<u32> {
+        Ok((a ^ b).count_ones())
+    }
+
     #[getter(width)]
     fn width(&self) -> PyResult<f64> {
         Ok(self.bitmap.size.width)
@@ -276,6 +583,25 @@ impl<'a> Bitmap {
     }
 }
 
+#[py::proto]
+impl<'p> PyObjectProtocol<'p> for Bitmap {
+    /// Bitmaps compare equal when `is_equal(other, tolerance=None)`
+    /// holds, i.e. an exact pixel-for-pixel match.
+    fn __richcmp__(&self, other: &Bitmap, op: CompareOp) -> PyResult<bool> {
+        match op {
+            CompareOp::Eq => Ok(self.bitmap.bitmap_eq(&other.bitmap, None)),
+            CompareOp::Ne => Ok(!self.bitmap.bitmap_eq(&other.bitmap, None)),
+            _ => Err(exc::TypeError::new("Bitmap only supports == and !=")),
+        }
+    }
+
+    fn __hash__(&self) -> PyResult<u64> {
+        let mut hasher = DefaultHasher::new();
+        self.bitmap.hash(&mut hasher);
+        Ok(hasher.finish())
+    }
+}
+
 /// This module defines the class `Bitmap` for accessing bitmaps and
 /// searching for bitmaps on-screen.
 ///
@@ -309,10 +635,313 @@ fn init(py: Python, m: &PyModule) -> PyResult<()> {
         Ok(result)
     }
 
+    /// Returns a `Bitmap` decoded from the image currently on the
+    /// system clipboard, or `None` if the clipboard holds no image.
+    ///
+    /// Exceptions:
+    ///     - `ValueError` is thrown if the clipboard image failed to
+    ///       parse.
+    #[pyfn(m, "bitmap_from_clipboard")]
+    fn bitmap_from_clipboard(python: Python) -> PyResult<Option<&Bitmap>> {
+        if let Some(data) = try!(read_clipboard_image()) {
+            let image = try!(image::load_from_memory(&data).map_err(FromImageError::from));
+            let bmp = autopilot::bitmap::Bitmap::new(image, None);
+            let result = try!(python.init_ref(|t| {
+                Bitmap {
+                    bitmap: bmp,
+                    token: t,
+                }
+            }));
+            Ok(Some(result))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Places `bmp` onto the system clipboard as a PNG image.
+    ///
+    /// Exceptions:
+    ///     - `IOError` is thrown if the bitmap couldn't be copied to
+    ///       the clipboard.
+    #[pyfn(m, "copy_to_clipboard")]
+    fn copy_to_clipboard(_python: Python, bmp: &Bitmap) -> PyResult<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        try!(
+            bmp.bitmap
+                .image
+                .write_to(&mut buffer, ImageFormat::PNG)
+                .map_err(FromImageError::from)
+        );
+        write_clipboard_image(&buffer)
+    }
+
     m.add_class::<Bitmap>()?;
     Ok(())
 }
 
+/// Reads the image currently on the system clipboard as encoded
+/// bytes (PNG), or `None` if the clipboard holds no image.
+///
+/// On Linux, the Wayland selection (`wl-clipboard`) is tried first,
+/// falling back to the X11 `CLIPBOARD` selection, mirroring the
+/// clipboard-image support Pillow gained via `grabclipboard`.
+#[cfg(target_os = "linux")]
+fn read_clipboard_image() -> PyResult<Option<Vec<u8>>> {
+    use std::io::Read;
+    use std::time::Duration;
+    use wl_clipboard_rs::paste::{get_contents, ClipboardType, MimeType, Seat};
+    use x11_clipboard::Clipboard;
+
+    match get_contents(ClipboardType::Regular, Seat::Unspecified, MimeType::Specific("image/png")) {
+        Ok((mut pipe, _mime)) => {
+            let mut data = Vec::new();
+            try!(
+                pipe.read_to_end(&mut data)
+                    .map_err(|e| exc::IOError::new(e.to_string()))
+            );
+            Ok(Some(data))
+        }
+        Err(_) => {
+            let clipboard = try!(Clipboard::new().map_err(|e| exc::IOError::new(e.to_string())));
+            let image_png = try!(intern_atom(&clipboard.getter.connection, "image/png"));
+            match clipboard.load(
+                clipboard.setter.atoms.clipboard,
+                image_png,
+                clipboard.setter.atoms.property,
+                Duration::from_secs(3),
+            ) {
+                Ok(data) => Ok(Some(data)),
+                Err(_) => Ok(None),
+            }
+        }
+    }
+}
+
+/// Places `data` (PNG-encoded image bytes) onto the system clipboard.
+///
+/// On Linux, the Wayland selection (`wl-clipboard`) is tried first,
+/// falling back to the X11 `CLIPBOARD` selection.
+#[cfg(target_os = "linux")]
+fn write_clipboard_image(data: &[u8]) -> PyResult<()> {
+    use wl_clipboard_rs::copy::{MimeType, Options, Source};
+    use x11_clipboard::Clipboard;
+
+    let result = Options::new().copy(
+        Source::Bytes(data.to_vec().into_boxed_slice()),
+        MimeType::Specific("image/png".to_string()),
+    );
+    if result.is_ok() {
+        return Ok(());
+    }
+    let clipboard = try!(Clipboard::new().map_err(|e| exc::IOError::new(e.to_string())));
+    let image_png = try!(intern_atom(&clipboard.setter.connection, "image/png"));
+    try!(
+        clipboard
+            .store(clipboard.setter.atoms.clipboard, image_png, data)
+            .map_err(|e| exc::IOError::new(e.to_string()))
+    );
+    Ok(())
+}
+
+/// Interns the X11 atom named `name` (e.g. `"image/png"`), for use as
+/// a selection conversion target that isn't one of the well-known
+/// atoms `x11_clipboard::Atoms` already carries.
+#[cfg(target_os = "linux")]
+fn intern_atom(connection: &x11_clipboard::xcb::Connection, name: &str) -> PyResult<x11_clipboard::xcb::Atom> {
+    let reply = try!(
+        x11_clipboard::xcb::intern_atom(connection, false, name)
+            .get_reply()
+            .map_err(|e| exc::IOError::new(e.to_string()))
+    );
+    Ok(reply.atom())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_clipboard_image() -> PyResult<Option<Vec<u8>>> {
+    Err(exc::NotImplementedError::new(
+        "Clipboard access is not yet implemented on this platform",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_clipboard_image(_data: &[u8]) -> PyResult<()> {
+    Err(exc::NotImplementedError::new(
+        "Clipboard access is not yet implemented on this platform",
+    ))
+}
+
+/// Converts a rect from point space to pixel space by multiplying by
+/// `scale`.
+fn scale_rect(rect: Rect, scale: f64) -> Rect {
+    Rect::new(
+        scale_point(rect.origin, scale),
+        Size::new(rect.size.width * scale, rect.size.height * scale),
+    )
+}
+
+/// Converts a point from point space to pixel space by multiplying
+/// by `scale`.
+fn scale_point(point: Point, scale: f64) -> Point {
+    Point::new(point.x * scale, point.y * scale)
+}
+
+/// Returns the grayscale pixels of `image` as `f64`s in row-major
+/// order, along with its width and height.
+fn luma_matrix(image: &image::DynamicImage) -> (Vec<f64>, u32, u32) {
+    let gray = image.to_luma();
+    let (width, height) = gray.dimensions();
+    let data = gray.into_raw().into_iter().map(|p| p as f64).collect();
+    (data, width, height)
+}
+
+/// Builds summed-area tables (integral images) of `data` and of its
+/// elementwise square, each `(width + 1) * (height + 1)` in size, so
+/// that the sum over any axis-aligned window can be queried in O(1)
+/// via `window_sum`.
+fn integral_images(data: &[f64], width: u32, height: u32) -> (Vec<f64>, Vec<f64>) {
+    let stride = (width + 1) as usize;
+    let mut sum = vec![0.0; stride * (height as usize + 1)];
+    let mut sum_sq = vec![0.0; stride * (height as usize + 1)];
+    for y in 0..height as usize {
+        let mut row_sum = 0.0;
+        let mut row_sum_sq = 0.0;
+        for x in 0..width as usize {
+            let v = data[y * width as usize + x];
+            row_sum += v;
+            row_sum_sq += v * v;
+            let idx = (y + 1) * stride + (x + 1);
+            let up = y * stride + (x + 1);
+            sum[idx] = sum[up] + row_sum;
+            sum_sq[idx] = sum_sq[up] + row_sum_sq;
+        }
+    }
+    (sum, sum_sq)
+}
+
+/// Returns the sum of the `width x height` window at `(x, y)` from a
+/// summed-area table built by `integral_images`.
+fn window_sum(sat: &[f64], stride: usize, x: usize, y: usize, width: usize, height: usize) -> f64 {
+    sat[(y + height) * stride + (x + width)] - sat[y * stride + (x + width)]
+        - sat[(y + height) * stride + x] + sat[y * stride + x]
+}
+
+/// Returns `sum(haystack_window * needle)` for the needle-sized
+/// window of `haystack` (of row length `haystack_width`) whose
+/// top-left corner is at `(x, y)`.
+fn window_dot(
+    haystack: &[f64],
+    haystack_width: usize,
+    x: usize,
+    y: usize,
+    needle: &[f64],
+    needle_width: usize,
+    needle_height: usize,
+) -> f64 {
+    let mut dot = 0.0;
+    for j in 0..needle_height {
+        let haystack_row = (y + j) * haystack_width + x;
+        let needle_row = j * needle_width;
+        for i in 0..needle_width {
+            dot += haystack[haystack_row + i] * needle[needle_row + i];
+        }
+    }
+    dot
+}
+
+/// Slides `needle` over `haystack`, computing the normalized
+/// cross-correlation coefficient at every offset inside `bounds`
+/// starting from `start`, and returns the top-left positions whose
+/// coefficient is `>= threshold`. Returns only the first match unless
+/// `find_all` is set. Window sums/variances are computed in O(1) via
+/// integral images; only the cross term is O(needle size) per
+/// window, matching the cost of the existing exact scan. `bounds` and
+/// `start` are in the same pixel space as `haystack`/`needle`
+/// themselves; callers in point space must scale first.
+fn find_bitmap_ncc_positions(
+    haystack_image: &image::DynamicImage,
+    needle_image: &image::DynamicImage,
+    threshold: f64,
+    bounds: Rect,
+    start: Point,
+    find_all: bool,
+) -> Vec<Point> {
+    let (haystack, haystack_width, haystack_height) = luma_matrix(haystack_image);
+    let (needle, needle_width, needle_height) = luma_matrix(needle_image);
+    let needle_count = (needle_width * needle_height) as f64;
+    if needle_count == 0.0 || needle_width > haystack_width || needle_height > haystack_height {
+        return Vec::new();
+    }
+
+    let needle_sum: f64 = needle.iter().sum();
+    let needle_mean = needle_sum / needle_count;
+    let needle_variance: f64 = needle
+        .iter()
+        .map(|v| (v - needle_mean) * (v - needle_mean))
+        .sum();
+
+    let (sat, sat_sq) = integral_images(&haystack, haystack_width, haystack_height);
+    let stride = (haystack_width + 1) as usize;
+
+    let x_min = bounds.origin.x.max(start.x).max(0.0) as usize;
+    let y_min = bounds.origin.y.max(start.y).max(0.0) as usize;
+    let x_max = (bounds.origin.x + bounds.size.width).min(haystack_width as f64) as usize;
+    let y_max = (bounds.origin.y + bounds.size.height).min(haystack_height as f64) as usize;
+    if x_max < needle_width as usize || y_max < needle_height as usize {
+        return Vec::new();
+    }
+
+    let mut results = Vec::new();
+    for y in y_min..=(y_max - needle_height as usize) {
+        for x in x_min..=(x_max - needle_width as usize) {
+            let window_sum_ = window_sum(
+                &sat,
+                stride,
+                x,
+                y,
+                needle_width as usize,
+                needle_height as usize,
+            );
+            let window_sum_sq = window_sum(
+                &sat_sq,
+                stride,
+                x,
+                y,
+                needle_width as usize,
+                needle_height as usize,
+            );
+            let window_mean = window_sum_ / needle_count;
+            let window_variance = window_sum_sq - window_mean * window_sum_;
+            let denominator = (window_variance * needle_variance).sqrt();
+            if denominator <= 0.0 {
+                continue;
+            }
+            let dot = window_dot(
+                &haystack,
+                haystack_width as usize,
+                x,
+                y,
+                &needle,
+                needle_width as usize,
+                needle_height as usize,
+            );
+            let ncc = (dot - needle_mean * window_sum_) / denominator;
+            if ncc >= threshold {
+                results.push(Point::new(x as f64, y as f64));
+                if !find_all {
+                    return results;
+                }
+            }
+        }
+    }
+    results
+}
+
+/// Packs an `(r, g, b, a)` tuple into an ARGB `u32`, with `a` in the
+/// most significant byte.
+fn pack_argb(color: (u8, u8, u8, u8)) -> u32 {
+    ((color.3 as u32) << 24) | ((color.0 as u32) << 16) | ((color.1 as u32) << 8) | (color.2 as u32)
+}
+
 fn image_format_from_extension(extension: &str) -> Option<ImageFormat> {
     let extension: &str = &(extension.to_lowercase());
     match extension {